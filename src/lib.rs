@@ -1,143 +1,98 @@
 //! Crate ruma_api contains core types used to define the requests and responses for each endpoint
 //! in the various [Matrix](https://matrix.org) API specifications.
 //! These types can be shared by client and server code for all Matrix APIs.
-//! When implementing a new Matrix API, each endpoint should have a type that implements `Endpoint`,
-//! plus the associated `Request` and `Response` types.
+//! When implementing a new Matrix API, each endpoint should use the `ruma_api!` macro to generate
+//! a `Request` type that implements `OutgoingRequest` and `IncomingRequest`, plus a `Response`
+//! type that implements `OutgoingResponse` and `IncomingResponse`.
 //!
 //! # Example
 //!
 //! ```rust,no_run
-//! # #![feature(try_from)]
+//! # extern crate bytes;
+//! # extern crate http;
+//! # extern crate percent_encoding;
+//! # #[macro_use]
 //! # extern crate ruma_api;
 //! # extern crate ruma_identifiers;
-//! # extern crate serde;
 //! # #[macro_use]
 //! # extern crate serde_derive;
 //! # extern crate serde_json;
+//! # extern crate serde_urlencoded;
 //! #
 //! # fn main() {
 //! /// PUT /_matrix/client/r0/directory/room/:room_alias
 //! pub mod create {
-//!     use std::convert::TryFrom;
-//!
-//!     use ruma_api::{self, Endpoint as ApiEndpoint, Info, Method};
-//!     use ruma_identifiers::{Error as RumaIdentifiersError, RoomAliasId, RoomId};
-//!     use serde_json::{Error as SerdeJsonError, from_slice, to_vec};
-//!
-//!     /// Endpoint for adding an alias to a room.
-//!     pub struct Endpoint;
-//!
-//!     /// An error when converting between `Request`/`Response` and
-//!     /// `ruma_api::Request`/`ruma_api::Response`.
-//!     pub enum Error {
-//!         /// An error when converting into a Matrix identifier.
-//!         RumaIdentifiers(RumaIdentifiersError),
-//!         /// An error when converting from JSON.
-//!         SerdeJson(SerdeJsonError),
-//!     }
-//!
-//!     /// Input parameters for a request to this endpoint.
-//!     pub struct Request {
-//!         /// The room alias to create.
-//!         pub room_alias: RoomAliasId,
-//!         /// The ID of the room being aliased.
-//!         pub room_id: RoomId,
-//!     }
-//!
-//!     #[derive(Deserialize, Serialize)]
-//!     struct RequestBody {
-//!         /// The ID of the room being aliased.
-//!         pub room_id: RoomId,
-//!     }
-//!
-//!     /// The response from this endpoint.
-//!     pub struct Response;
-//!
-//!     impl ruma_api::Endpoint for Endpoint {
-//!         type Request = Request;
-//!         type Response = Response;
-//!
-//!         fn info() -> Info {
-//!             Info {
-//!                 description: "Add an alias to a room.",
-//!                 name: "create_alias",
-//!                 rate_limited: false,
-//!                 request_method: Method::Put,
-//!                 requires_authentication: true,
-//!                 router_path: "/_matrix/client/r0/directory/room/:room_alias",
-//!             }
-//!         }
-//!     }
-//!
-//!     impl Into<ruma_api::Request> for Request {
-//!         fn into(self) -> ruma_api::Request {
-//!             let request_body = RequestBody {
-//!                 room_id: self.room_id,
-//!             };
-//!
-//!             ruma_api::Request {
-//!                 body: to_vec(&request_body).expect("request body should serialize"),
-//!                 headers: Vec::new(),
-//!                 method: Endpoint::info().request_method,
-//!                 path: format!("/_matrix/client/r0/directory/room/{}", self.room_alias),
-//!                 query: Vec::new(),
-//!             }
-//!         }
-//!     }
-//!
-//!     impl TryFrom<ruma_api::Request> for Request {
-//!         type Err = Error;
-//!
-//!         fn try_from(request: ruma_api::Request) -> Result<Self, Self::Err> {
-//!             let parts: Vec<&str> = request.path.split('/').collect();
-//!             let request_body: RequestBody = from_slice(&request.body)?;
-//!
-//!             Ok(Request {
-//!                 room_alias: RoomAliasId::try_from(parts[6])?,
-//!                 room_id: request_body.room_id,
-//!             })
-//!         }
-//!     }
+//!     use ruma_identifiers::{RoomAliasId, RoomId};
 //!
-//!     impl Into<ruma_api::Response> for Response {
-//!         fn into(self) -> ruma_api::Response {
-//!             ruma_api::Response {
-//!                 body: Vec::new(),
-//!                 headers: Vec::new(),
-//!                 status: 200,
-//!             }
-//!         }
+//!     /// The body of a Matrix error response, returned when this endpoint is rate limited.
+//!     #[derive(Debug, Deserialize)]
+//!     pub struct RateLimitError {
+//!         /// A machine-readable error code, e.g. `M_LIMIT_EXCEEDED`.
+//!         pub errcode: String,
+//!         /// How long the client should wait before retrying, in milliseconds.
+//!         pub retry_after_ms: Option<u64>,
 //!     }
 //!
-//!     impl TryFrom<ruma_api::Response> for Response {
-//!         type Err = Error;
-//!
-//!         fn try_from(_: ruma_api::Response) -> Result<Self, Self::Err> {
-//!             Ok(Response)
+//!     ruma_api! {
+//!         metadata: {
+//!             description: "Add an alias to a room.",
+//!             method: PUT,
+//!             name: "create_alias",
+//!             path: "/_matrix/client/r0/directory/room/:room_alias",
+//!             rate_limited: false,
+//!             auth_scheme: AccessToken,
+//!             error: RateLimitError,
 //!         }
-//!     }
 //!
-//!     impl From<SerdeJsonError> for Error {
-//!         fn from(error: SerdeJsonError) -> Self {
-//!             Error::SerdeJson(error)
+//!         request: {
+//!             /// The room alias to create.
+//!             #[ruma_api(path)]
+//!             pub room_alias: RoomAliasId,
+//!             /// The ID of the room being aliased.
+//!             pub room_id: RoomId,
 //!         }
-//!     }
 //!
-//!     impl From<RumaIdentifiersError> for Error {
-//!         fn from(error: RumaIdentifiersError) -> Self {
-//!             Error::RumaIdentifiers(error)
-//!         }
+//!         response: {}
 //!     }
 //! }
 //! # }
 
-#![feature(try_from)]
 #![deny(missing_debug_implementations)]
 #![deny(missing_docs)]
 
-use std::convert::TryFrom;
+extern crate bytes;
+extern crate http;
+#[macro_use]
+extern crate percent_encoding;
+
+define_encode_set! {
+    /// Like `percent_encoding::QUERY_ENCODE_SET`, but also encodes `&` and `=`, the sub-delimiters
+    /// that separate query pairs. Used to percent-encode the *value* of a repeated-key `Vec<T>`
+    /// query parameter, so a value containing either can't be mistaken for another key/value pair.
+    pub QUERY_VALUE_ENCODE_SET = [percent_encoding::QUERY_ENCODE_SET] | { '&', '=' }
+}
+
+#[cfg(test)]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(test)]
+extern crate serde_json;
+#[cfg(test)]
+extern crate serde_urlencoded;
+
+#[macro_use]
+mod macros;
+
+#[cfg(test)]
+mod tests;
 
-/// Information about an `Endpoint`.
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+
+pub use http::Method;
+
+/// Information about an endpoint.
 #[derive(Clone, Copy, Debug)]
 pub struct Info {
     /// A human-readable description of the endpoint.
@@ -148,76 +103,291 @@ pub struct Info {
     pub rate_limited: bool,
     /// The HTTP method used by this endpoint.
     pub request_method: Method,
-    /// Whether or not the server requires an authenticated user for this endpoint.
-    pub requires_authentication: bool,
-    /// The path of this endpoint's URL, with variable names where path parameters should be filled
-    /// in during a request.
-    ///
-    /// This value is suitable for creating routes with `Router` from the router crate.
-    pub router_path: &'static str,
+    /// What authentication scheme the server requires for this endpoint.
+    pub auth_scheme: AuthScheme,
+    /// The paths of this endpoint's URL across the Matrix versions that define it, with variable
+    /// names where path parameters should be filled in during a request.
+    pub history: VersionHistory,
 }
 
-/// HTTP request methods used in Matrix APIs.
+/// The authentication scheme used by a Matrix endpoint, if any.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AuthScheme {
+    /// No authentication is performed.
+    None,
+    /// Authentication is performed by including an access token, either as a query parameter or
+    /// in the `Authorization` header, as described in the
+    /// [Matrix client-server API spec](https://matrix.org/docs/spec/client_server/r0.4.0.html#using-access-tokens).
+    AccessToken,
+    /// Authentication is performed by including an access token as a query parameter only, as
+    /// described in the same part of the spec as `AuthScheme::AccessToken`. Used for endpoints
+    /// that serve downloadable content and so can't rely on custom request headers.
+    QueryOnlyAccessToken,
+    /// Authentication is performed by signing the request with a homeserver's private key, as
+    /// described in the
+    /// [Matrix server-server API spec](https://matrix.org/docs/spec/server_server/r0.1.1#authentication).
+    ServerSignatures,
+}
+
+/// A version of the Matrix specification, as tracked by an endpoint's `VersionHistory`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
+pub enum MatrixVersion {
+    /// The legacy, unnumbered `r0` client-server endpoints that predate `vX.Y` versioning.
+    R0,
+    /// Matrix 1.1
+    V1_1,
+    /// Matrix 1.2
+    V1_2,
+    /// Matrix 1.3
+    V1_3,
+}
+
+/// The history of the path(s) an endpoint has been served at across Matrix versions.
+///
+/// A single endpoint definition can target multiple homeserver versions by listing every path it
+/// has ever been served at; `select_path` then picks the most recent one a given server actually
+/// supports.
 #[derive(Clone, Copy, Debug)]
-pub enum Method {
-    /// DELETE
-    Delete,
-    /// GET
-    Get,
-    /// POST
-    Post,
-    /// PUT
-    Put,
+pub struct VersionHistory {
+    paths: &'static [(MatrixVersion, &'static str)],
+    deprecated: Option<MatrixVersion>,
+    removed: Option<MatrixVersion>,
 }
 
-/// An API endpoint.
-pub trait Endpoint {
-    /// Request data from the client.
-    type Request: Into<Request> + TryFrom<Request>;
+impl VersionHistory {
+    /// Creates a new `VersionHistory`.
+    ///
+    /// `paths` must be given oldest version first, and must not be empty.
+    pub const fn new(
+        paths: &'static [(MatrixVersion, &'static str)],
+        deprecated: Option<MatrixVersion>,
+        removed: Option<MatrixVersion>,
+    ) -> Self {
+        VersionHistory {
+            paths,
+            deprecated,
+            removed,
+        }
+    }
 
-    /// Response data from the server.
-    type Response: Into<Response> + TryFrom<Response>;
+    /// The Matrix version this endpoint was deprecated in, if any.
+    pub fn deprecated(&self) -> Option<MatrixVersion> {
+        self.deprecated
+    }
+
+    /// The Matrix version this endpoint was removed in, if any.
+    pub fn removed(&self) -> Option<MatrixVersion> {
+        self.removed
+    }
+
+    /// Picks the path belonging to the newest version in `paths` that is no newer than the
+    /// newest version in `supported_versions`, if any.
+    ///
+    /// A server only ever advertises the newest version it supports (not every version in its
+    /// history), so this matches on an upper bound rather than requiring `paths` and
+    /// `supported_versions` to share an exact version.
+    pub fn select_path(&self, supported_versions: &[MatrixVersion]) -> Option<&'static str> {
+        let newest_supported = supported_versions.iter().max()?;
+
+        self.paths
+            .iter()
+            .rev()
+            .find(|&&(version, _)| version <= *newest_supported)
+            .map(|&(_, path)| path)
+    }
+}
+
+/// A request from a client, as sent to a server.
+///
+/// Implemented by the client-side request type of an endpoint. Converts the strongly-typed
+/// request into a real `http::Request`, and knows how to parse the corresponding response back
+/// out of the `http::Response` it eventually receives.
+pub trait OutgoingRequest: Sized {
+    /// The type of the response expected for this request, as parsed back out of a server's
+    /// `http::Response`.
+    type IncomingResponse: IncomingResponse;
 
     /// General information about the endpoint.
     fn info() -> Info;
+
+    /// Tries to convert this request into an `http::Request`, targeting the path that
+    /// `VersionHistory::select_path` picks for `supported_versions`: the most recent path this
+    /// endpoint has at or below the newest version in `supported_versions`.
+    ///
+    /// The body type `T` is left generic so that this method does not bind `ruma-api` to any
+    /// particular HTTP client library.
+    fn try_into_http_request<T: Default + bytes::BufMut>(
+        self,
+        supported_versions: &[MatrixVersion],
+    ) -> Result<http::Request<T>, Error>;
 }
 
-/// An HTTP request.
+/// A request from a client, as received by a server.
 ///
-/// This structure is intentionally abstract so as not to bind `ruma-api` to any particular HTTP
-/// library.
-/// A library implementing `Endpoint`s must provide conversions between their own request types and
-/// `Request`.
-/// Programs consuming such a Matrix API library should then provide conversions between their HTTP
-/// library of choice and `Request`.
-#[derive(Clone, Debug)]
-pub struct Request {
-    /// The request body.
-    pub body: Vec<u8>,
-    /// The HTTP request headers.
-    pub headers: Vec<(Vec<u8>, Vec<u8>)>,
-    /// The HTTP request method.
-    pub method: Method,
-    /// The path component of the request's URL.
-    pub path: String,
-    /// The query string component of the request's URL.
-    pub query: Vec<(String, String)>
-}
-
-/// An HTTP response.
+/// Implemented by the server-side request type of an endpoint. Parses a real `http::Request`
+/// into the strongly-typed request, and knows how to convert the response it produces into an
+/// `http::Response` to send back to the client.
+pub trait IncomingRequest: Sized {
+    /// The type of the response this endpoint produces, to be converted into an `http::Response`.
+    type OutgoingResponse: OutgoingResponse;
+
+    /// General information about the endpoint.
+    fn info() -> Info;
+
+    /// Tries to turn an `http::Request` into this request type.
+    fn try_from_http_request<T: AsRef<[u8]>>(request: http::Request<T>) -> Result<Self, Error>;
+}
+
+/// A response to a client, as sent by a server.
+pub trait OutgoingResponse: Sized {
+    /// Tries to convert this response into an `http::Response`.
+    fn try_into_http_response<T: Default + bytes::BufMut>(
+        self,
+    ) -> Result<http::Response<T>, Error>;
+}
+
+/// A response to a client, as received from a server.
+pub trait IncomingResponse: Sized {
+    /// The type of the error returned by the endpoint on non-success responses, if the endpoint
+    /// has a structured Matrix error body (`{ "errcode": ..., "error": ... }`) to deserialize
+    /// into.
+    type EndpointError;
+
+    /// Tries to turn an `http::Response` into this response type.
+    ///
+    /// A non-success status code is treated as an error. If the response body can be
+    /// deserialized into `EndpointError`, the error carries it as `ServerError::Known`; otherwise
+    /// only the status code is reported, as `ServerError::Unknown`.
+    fn try_from_http_response<T: AsRef<[u8]>>(
+        response: http::Response<T>,
+    ) -> Result<Self, FromHttpResponseError<Self::EndpointError>>;
+}
+
+/// An error converting a request or response to or from its `http` crate equivalent.
+#[derive(Debug)]
+pub struct Error(String);
+
+impl Error {
+    /// Creates a new `Error` from anything that can be displayed, such as an error from a
+    /// (de)serialization library used by an endpoint's `Request` or `Response` type.
+    pub fn new<T: fmt::Display>(message: T) -> Self {
+        Error(message.to_string())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<http::Error> for Error {
+    fn from(error: http::Error) -> Self {
+        Error(error.to_string())
+    }
+}
+
+/// An error when converting an `http::Response` into one of ruma-api's endpoint response types.
+#[derive(Debug)]
+pub enum FromHttpResponseError<E> {
+    /// The server returned a non-success status code, optionally with a structured Matrix error
+    /// body.
+    Http(ServerError<E>),
+    /// The response body or one of its expected headers couldn't be deserialized into the
+    /// expected response type.
+    Deserialization(Error),
+}
+
+impl<E> From<Error> for FromHttpResponseError<E> {
+    fn from(error: Error) -> Self {
+        FromHttpResponseError::Deserialization(error)
+    }
+}
+
+impl<E: fmt::Debug> fmt::Display for FromHttpResponseError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FromHttpResponseError::Http(ref error) => write!(f, "{:?}", error),
+            FromHttpResponseError::Deserialization(ref error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// An error received from the server in response to a request, as distinct from a failure to
+/// deserialize that response.
+#[derive(Debug)]
+pub enum ServerError<E> {
+    /// The server returned a structured Matrix error body that was deserialized into the
+    /// endpoint's own error type.
+    Known(E),
+    /// The server returned an error response without a structured Matrix error body, or one that
+    /// couldn't be deserialized. Only the status code is known.
+    Unknown(http::StatusCode),
+}
+
+/// Finds the path segment of `path` that lines up with `placeholder` (a `:name` path parameter)
+/// in `template`, percent-decodes it, and parses it into `T`.
+///
+/// Used by code generated from the `ruma_api!` macro; not meant to be called directly.
+#[doc(hidden)]
+pub fn path_segment<T>(path: &str, template: &str, placeholder: &str) -> Result<T, Error>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let index = template
+        .split('/')
+        .position(|segment| segment == placeholder)
+        .ok_or_else(|| Error::new(format!("{} has no {} path segment", template, placeholder)))?;
+
+    let raw_segment = path
+        .split('/')
+        .nth(index)
+        .ok_or_else(|| Error::new(format!("{} is missing a path segment", path)))?;
+
+    let segment = percent_encoding::percent_decode(raw_segment.as_bytes())
+        .decode_utf8()
+        .map_err(Error::new)?;
+
+    segment.parse().map_err(Error::new)
+}
+
+/// Finds every occurrence of `name` in `query` (an `a=b&c=d`-style query string), percent-decodes
+/// its value, and parses the values into a `Vec<T>`, preserving their order.
 ///
-/// This structure is intentionally abstract so as not to bind `ruma-api` to any particular HTTP
-/// library.
-/// A library implementing `Endpoint`s must provide conversions between their own response types and
-/// `Request`.
-/// Programs consuming such a Matrix API library should then provide conversions between their HTTP
-/// library of choice and `Response`.
-#[derive(Clone, Debug)]
-pub struct Response {
-    /// The request body.
-    pub body: Vec<u8>,
-    /// The HTTP request headers.
-    pub headers: Vec<(Vec<u8>, Vec<u8>)>,
-    /// The HTTP status code.
-    pub status: u16,
+/// Used by code generated from the `ruma_api!` macro for `Vec<T>`-typed `#[ruma_api(query)]`
+/// fields, which are sent as repeated keys rather than through `serde_urlencoded`; not meant to
+/// be called directly.
+#[doc(hidden)]
+pub fn query_pair_sequence<T>(query: &str, name: &str) -> Result<Vec<T>, Error>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    query
+        .split('&')
+        .filter_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            let key = parts.next()?;
+            let value = parts.next()?;
+            if key == name {
+                Some(value)
+            } else {
+                None
+            }
+        })
+        .map(|raw_value| {
+            let value = percent_encoding::percent_decode(raw_value.as_bytes())
+                .decode_utf8()
+                .map_err(Error::new)?;
+
+            value.parse().map_err(Error::new)
+        })
+        .collect()
 }
@@ -1,32 +1,717 @@
-/// Convenience macro for quickly creating an API endpoint.
+/// Defines a Matrix API endpoint, generating its `Request` and `Response` types along with their
+/// `OutgoingRequest`/`IncomingRequest`/`OutgoingResponse`/`IncomingResponse` implementations.
+///
+/// `metadata` takes either a `path: "..."` entry, for an endpoint with a single, unversioned path,
+/// or a `history: { (VERSION, "..."), ... }` entry (oldest version first) for an endpoint whose
+/// path has changed across Matrix versions; `OutgoingRequest::try_into_http_request` then picks
+/// the newest path a given server supports via `VersionHistory::select_path`. `metadata` also
+/// accepts an optional `error: SomeType,` entry naming the type non-success responses should be
+/// deserialized into as `ServerError::Known`; it defaults to `()` (always `ServerError::Unknown`)
+/// when omitted.
+///
+/// Fields of `request` may be tagged with one of the following attributes to say where in the
+/// HTTP request they belong. A field with no attribute is collected into the JSON request body.
+/// Fields of `response` likewise go into the JSON response body, except those tagged
+/// `#[ruma_api(header = "HEADER_NAME")]`, which are sent as the named HTTP header.
+///
+/// * `#[ruma_api(path)]` -- substituted into the `:name` placeholders of the endpoint's path.
+/// * `#[ruma_api(query)]` -- sent as a query string parameter. A field of type `Vec<T>` is sent as
+///   one repeated key (`name=a&name=b`) rather than a single serialized value.
+/// * `#[ruma_api(header = "HEADER_NAME")]` -- sent as the named HTTP header.
+///
+/// This removes the need to hand-write `Into`/`TryFrom` conversions between an endpoint's own
+/// `Request`/`Response` types and `http::Request`/`http::Response`.
 #[macro_export]
-macro_rules! endpoint {
+macro_rules! ruma_api {
     (
+        metadata: {
+            description: $description:expr,
+            method: $method:ident,
+            name: $name:expr,
+            path: $path:expr,
+            rate_limited: $rate_limited:expr,
+            auth_scheme: $auth_scheme:ident,
+            error: $error_ty:ty,
+        }
+        request: { $($request:tt)* }
+        response: { $($response:tt)* }
+    ) => {
+        $crate::__ruma_api_request! {
+            metadata: {
+                description: $description,
+                method: $method,
+                name: $name,
+                path: $path,
+                rate_limited: $rate_limited,
+                auth_scheme: $auth_scheme,
+            }
+            fields: { $($request)* }
+        }
+
+        $crate::__ruma_api_response! {
+            error: $error_ty,
+            fields: { $($response)* }
+        }
+    };
+    (
+        metadata: {
+            description: $description:expr,
+            method: $method:ident,
+            name: $name:expr,
+            path: $path:expr,
+            rate_limited: $rate_limited:expr,
+            auth_scheme: $auth_scheme:ident,
+        }
+        request: { $($request:tt)* }
+        response: { $($response:tt)* }
+    ) => {
+        $crate::__ruma_api_request! {
+            metadata: {
+                description: $description,
+                method: $method,
+                name: $name,
+                path: $path,
+                rate_limited: $rate_limited,
+                auth_scheme: $auth_scheme,
+            }
+            fields: { $($request)* }
+        }
+
+        $crate::__ruma_api_response! {
+            error: (),
+            fields: { $($response)* }
+        }
+    };
+    (
+        metadata: {
+            description: $description:expr,
+            method: $method:ident,
+            name: $name:expr,
+            history: { $($history:tt)* },
+            rate_limited: $rate_limited:expr,
+            auth_scheme: $auth_scheme:ident,
+            error: $error_ty:ty,
+        }
+        request: { $($request:tt)* }
+        response: { $($response:tt)* }
+    ) => {
+        $crate::__ruma_api_request! {
+            metadata: {
+                description: $description,
+                method: $method,
+                name: $name,
+                history: { $($history)* },
+                rate_limited: $rate_limited,
+                auth_scheme: $auth_scheme,
+            }
+            fields: { $($request)* }
+        }
+
+        $crate::__ruma_api_response! {
+            error: $error_ty,
+            fields: { $($response)* }
+        }
+    };
+    (
+        metadata: {
+            description: $description:expr,
+            method: $method:ident,
+            name: $name:expr,
+            history: { $($history:tt)* },
+            rate_limited: $rate_limited:expr,
+            auth_scheme: $auth_scheme:ident,
+        }
+        request: { $($request:tt)* }
+        response: { $($response:tt)* }
+    ) => {
+        $crate::__ruma_api_request! {
+            metadata: {
+                description: $description,
+                method: $method,
+                name: $name,
+                history: { $($history)* },
+                rate_limited: $rate_limited,
+                auth_scheme: $auth_scheme,
+            }
+            fields: { $($request)* }
+        }
+
+        $crate::__ruma_api_response! {
+            error: (),
+            fields: { $($response)* }
+        }
+    };
+}
+
+/// Implementation detail of `ruma_api!`. Not public API.
+#[macro_export]
+#[doc(hidden)]
+macro_rules! __ruma_api_request {
+    (
+        metadata: { $($metadata:tt)* }
+        fields: { $($fields:tt)* }
+    ) => {
+        __ruma_api_request! {
+            @munch
+            metadata: { $($metadata)* }
+            fields_left: { $($fields)* }
+            path: []
+            query: []
+            query_vec: []
+            header: []
+            body: []
+        }
+    };
+
+    // A `#[ruma_api(path)]` field is peeled off the front and sorted into `path`.
+    (
+        @munch
+        metadata: { $($metadata:tt)* }
+        fields_left: {
+            $(#[doc = $doc:expr])*
+            #[ruma_api(path)]
+            pub $field:ident: $ty:ty,
+            $($rest:tt)*
+        }
+        path: [ $($path_field:tt)* ]
+        query: [ $($query_field:tt)* ]
+        query_vec: [ $($query_vec_field:tt)* ]
+        header: [ $($header_field:tt)* ]
+        body: [ $($body_field:tt)* ]
+    ) => {
+        __ruma_api_request! {
+            @munch
+            metadata: { $($metadata)* }
+            fields_left: { $($rest)* }
+            path: [ $($path_field)* { [$(#[doc = $doc])*] $field: $ty } ]
+            query: [ $($query_field)* ]
+            query_vec: [ $($query_vec_field)* ]
+            header: [ $($header_field)* ]
+            body: [ $($body_field)* ]
+        }
+    };
+
+    // A `#[ruma_api(query)]` field of type `Vec<T>` is peeled off the front and sorted into
+    // `query_vec`, so it can be sent as a repeated key instead of going through the
+    // `serde_urlencoded`-serialized query struct (which can't represent sequence-valued fields).
+    // This arm must come before the general `query` arm below, since `Vec<T>` also matches `$ty:ty`.
+    (
+        @munch
+        metadata: { $($metadata:tt)* }
+        fields_left: {
+            $(#[doc = $doc:expr])*
+            #[ruma_api(query)]
+            pub $field:ident: Vec<$elem_ty:ty>,
+            $($rest:tt)*
+        }
+        path: [ $($path_field:tt)* ]
+        query: [ $($query_field:tt)* ]
+        query_vec: [ $($query_vec_field:tt)* ]
+        header: [ $($header_field:tt)* ]
+        body: [ $($body_field:tt)* ]
+    ) => {
+        __ruma_api_request! {
+            @munch
+            metadata: { $($metadata)* }
+            fields_left: { $($rest)* }
+            path: [ $($path_field)* ]
+            query: [ $($query_field)* ]
+            query_vec: [ $($query_vec_field)* { [$(#[doc = $doc])*] $field: $elem_ty } ]
+            header: [ $($header_field)* ]
+            body: [ $($body_field)* ]
+        }
+    };
+
+    // A `#[ruma_api(query)]` field is peeled off the front and sorted into `query`.
+    (
+        @munch
+        metadata: { $($metadata:tt)* }
+        fields_left: {
+            $(#[doc = $doc:expr])*
+            #[ruma_api(query)]
+            pub $field:ident: $ty:ty,
+            $($rest:tt)*
+        }
+        path: [ $($path_field:tt)* ]
+        query: [ $($query_field:tt)* ]
+        query_vec: [ $($query_vec_field:tt)* ]
+        header: [ $($header_field:tt)* ]
+        body: [ $($body_field:tt)* ]
+    ) => {
+        __ruma_api_request! {
+            @munch
+            metadata: { $($metadata)* }
+            fields_left: { $($rest)* }
+            path: [ $($path_field)* ]
+            query: [ $($query_field)* { [$(#[doc = $doc])*] $field: $ty } ]
+            query_vec: [ $($query_vec_field)* ]
+            header: [ $($header_field)* ]
+            body: [ $($body_field)* ]
+        }
+    };
+
+    // A `#[ruma_api(header = "...")]` field is peeled off the front and sorted into `header`.
+    (
+        @munch
+        metadata: { $($metadata:tt)* }
+        fields_left: {
+            $(#[doc = $doc:expr])*
+            #[ruma_api(header = $header_name:expr)]
+            pub $field:ident: $ty:ty,
+            $($rest:tt)*
+        }
+        path: [ $($path_field:tt)* ]
+        query: [ $($query_field:tt)* ]
+        query_vec: [ $($query_vec_field:tt)* ]
+        header: [ $($header_field:tt)* ]
+        body: [ $($body_field:tt)* ]
+    ) => {
+        __ruma_api_request! {
+            @munch
+            metadata: { $($metadata)* }
+            fields_left: { $($rest)* }
+            path: [ $($path_field)* ]
+            query: [ $($query_field)* ]
+            query_vec: [ $($query_vec_field)* ]
+            header: [ $($header_field)* { [$(#[doc = $doc])*] $field: $ty, $header_name } ]
+            body: [ $($body_field)* ]
+        }
+    };
+
+    // An unattributed field is peeled off the front and sorted into `body`.
+    (
+        @munch
+        metadata: { $($metadata:tt)* }
+        fields_left: {
+            $(#[$doc:meta])*
+            pub $field:ident: $ty:ty,
+            $($rest:tt)*
+        }
+        path: [ $($path_field:tt)* ]
+        query: [ $($query_field:tt)* ]
+        query_vec: [ $($query_vec_field:tt)* ]
+        header: [ $($header_field:tt)* ]
+        body: [ $($body_field:tt)* ]
+    ) => {
+        __ruma_api_request! {
+            @munch
+            metadata: { $($metadata)* }
+            fields_left: { $($rest)* }
+            path: [ $($path_field)* ]
+            query: [ $($query_field)* ]
+            query_vec: [ $($query_vec_field)* ]
+            header: [ $($header_field)* ]
+            body: [ $($body_field)* { [$(#[$doc])*] $field: $ty } ]
+        }
+    };
+
+    // No fields left to munch, endpoint has a single `path`: resolve the single-entry
+    // `VersionHistory` and hand off to `@emit`.
+    (
+        @munch
+        metadata: {
+            description: $description:expr,
+            method: $method:ident,
+            name: $name:expr,
+            path: $path:expr,
+            rate_limited: $rate_limited:expr,
+            auth_scheme: $auth_scheme:ident,
+        }
+        fields_left: {}
+        path: [ $($path_field:tt)* ]
+        query: [ $($query_field:tt)* ]
+        query_vec: [ $($query_vec_field:tt)* ]
+        header: [ $($header_field:tt)* ]
+        body: [ $($body_field:tt)* ]
+    ) => {
+        __ruma_api_request! {
+            @emit
+            decode_path: $path,
+            history: $crate::VersionHistory::new(&[($crate::MatrixVersion::R0, $path)], None, None),
+            description: $description,
+            method: $method,
+            name: $name,
+            rate_limited: $rate_limited,
+            auth_scheme: $auth_scheme,
+            path: [ $($path_field)* ]
+            query: [ $($query_field)* ]
+            query_vec: [ $($query_vec_field)* ]
+            header: [ $($header_field)* ]
+            body: [ $($body_field)* ]
+        }
+    };
+
+    // No fields left to munch, endpoint has a `history` of paths across Matrix versions: resolve
+    // the `VersionHistory` and hand off to `@emit`. Path params are decoded against the oldest
+    // path, since every version of an endpoint's path is assumed to place them identically.
+    (
+        @munch
+        metadata: {
+            description: $description:expr,
+            method: $method:ident,
+            name: $name:expr,
+            history: { ($history_version:ident, $history_path:expr) $(, ($more_version:ident, $more_path:expr))* $(,)* },
+            rate_limited: $rate_limited:expr,
+            auth_scheme: $auth_scheme:ident,
+        }
+        fields_left: {}
+        path: [ $($path_field:tt)* ]
+        query: [ $($query_field:tt)* ]
+        query_vec: [ $($query_vec_field:tt)* ]
+        header: [ $($header_field:tt)* ]
+        body: [ $($body_field:tt)* ]
+    ) => {
+        __ruma_api_request! {
+            @emit
+            decode_path: $history_path,
+            history: $crate::VersionHistory::new(
+                &[
+                    ($crate::MatrixVersion::$history_version, $history_path),
+                    $(($crate::MatrixVersion::$more_version, $more_path),)*
+                ],
+                None,
+                None,
+            ),
+            description: $description,
+            method: $method,
+            name: $name,
+            rate_limited: $rate_limited,
+            auth_scheme: $auth_scheme,
+            path: [ $($path_field)* ]
+            query: [ $($query_field)* ]
+            query_vec: [ $($query_vec_field)* ]
+            header: [ $($header_field)* ]
+            body: [ $($body_field)* ]
+        }
+    };
+
+    // Emits the `Request` type and its trait impls, shared by both the single-`path` and
+    // `history` endpoint definitions above.
+    (
+        @emit
+        decode_path: $decode_path:expr,
+        history: $history:expr,
         description: $description:expr,
+        method: $method:ident,
         name: $name:expr,
         rate_limited: $rate_limited:expr,
-        request_method: $request_method:ident,
-        requires_authentication: $requires_authentication:expr,
-        router_path: $router_path:expr
+        auth_scheme: $auth_scheme:ident,
+        path: [ $({ [$(#[$path_doc:meta])*] $path_field:ident: $path_ty:ty })* ]
+        query: [ $({ [$(#[$query_doc:meta])*] $query_field:ident: $query_ty:ty })* ]
+        query_vec: [ $({ [$(#[$query_vec_doc:meta])*] $query_vec_field:ident: $query_vec_ty:ty })* ]
+        header: [ $({ [$(#[$header_doc:meta])*] $header_field:ident: $header_ty:ty, $header_name:expr })* ]
+        body: [ $({ [$(#[$body_doc:meta])*] $body_field:ident: $body_ty:ty })* ]
     ) => {
-        #[doc=$description]
-        #[derive(Clone, Copy, Debug)]
-        pub struct Endpoint;
+        /// Data for a request to this endpoint.
+        #[derive(Clone, Debug)]
+        pub struct Request {
+            $( $(#[$path_doc])* pub $path_field: $path_ty, )*
+            $( $(#[$query_doc])* pub $query_field: $query_ty, )*
+            $( $(#[$query_vec_doc])* pub $query_vec_field: Vec<$query_vec_ty>, )*
+            $( $(#[$header_doc])* pub $header_field: $header_ty, )*
+            $( $(#[$body_doc])* pub $body_field: $body_ty, )*
+        }
+
+        #[derive(Serialize)]
+        struct RequestBody {
+            $( $body_field: $body_ty, )*
+        }
+
+        #[derive(Deserialize)]
+        struct IncomingRequestBody {
+            $( $body_field: $body_ty, )*
+        }
+
+        #[derive(Serialize)]
+        struct RequestQuery {
+            $( $query_field: $query_ty, )*
+        }
+
+        #[derive(Deserialize)]
+        struct IncomingRequestQuery {
+            $( $query_field: $query_ty, )*
+        }
+
+        fn info() -> $crate::Info {
+            $crate::Info {
+                description: $description,
+                name: $name,
+                rate_limited: $rate_limited,
+                request_method: $crate::Method::$method,
+                auth_scheme: $crate::AuthScheme::$auth_scheme,
+                history: $history,
+            }
+        }
+
+        impl $crate::OutgoingRequest for Request {
+            type IncomingResponse = Response;
+
+            fn info() -> $crate::Info {
+                info()
+            }
 
-        impl $crate::Endpoint for Endpoint {
-            type Request = Request;
-            type Response = Response;
+            fn try_into_http_request<T: Default + bytes::BufMut>(
+                self,
+                supported_versions: &[$crate::MatrixVersion],
+            ) -> Result<http::Request<T>, $crate::Error> {
+                let path_template = info().history.select_path(supported_versions).ok_or_else(
+                    || $crate::Error::new("endpoint unsupported by given Matrix versions"),
+                )?;
+
+                #[allow(unused_mut)]
+                let mut path = path_template.to_owned();
+                $(
+                    path = path.replace(
+                        concat!(":", stringify!($path_field)),
+                        &percent_encoding::percent_encode(
+                            self.$path_field.to_string().as_bytes(),
+                            percent_encoding::PATH_SEGMENT_ENCODE_SET,
+                        ).to_string(),
+                    );
+                )*
+
+                #[allow(unused_mut)]
+                let mut query_parts: Vec<String> = Vec::new();
+
+                let request_query = RequestQuery {
+                    $( $query_field: self.$query_field, )*
+                };
+                let scalar_query = serde_urlencoded::to_string(&request_query)
+                    .map_err($crate::Error::new)?;
+                if !scalar_query.is_empty() {
+                    query_parts.push(scalar_query);
+                }
+
+                $(
+                    for value in &self.$query_vec_field {
+                        query_parts.push(format!(
+                            "{}={}",
+                            stringify!($query_vec_field),
+                            percent_encoding::percent_encode(
+                                value.to_string().as_bytes(),
+                                $crate::QUERY_VALUE_ENCODE_SET,
+                            ),
+                        ));
+                    }
+                )*
+
+                if !query_parts.is_empty() {
+                    path.push('?');
+                    path.push_str(&query_parts.join("&"));
+                }
+
+                let request_body = RequestBody {
+                    $( $body_field: self.$body_field, )*
+                };
+
+                let mut body = T::default();
+                body.put_slice(
+                    &serde_json::to_vec(&request_body).map_err($crate::Error::new)?,
+                );
+
+                #[allow(unused_mut)]
+                let mut builder = http::Request::builder().method(info().request_method).uri(path);
+                $( builder = builder.header($header_name, self.$header_field.to_string()); )*
+                builder.body(body).map_err($crate::Error::from)
+            }
+        }
+
+        impl $crate::IncomingRequest for Request {
+            type OutgoingResponse = Response;
 
             fn info() -> $crate::Info {
-                $crate::Info {
-                    description: $description,
-                    name: $name,
-                    rate_limited: $rate_limited,
-                    request_method: $crate::Method::$request_method,
-                    requires_authentication: $requires_authentication,
-                    router_path: $router_path,
+                info()
+            }
+
+            fn try_from_http_request<T: AsRef<[u8]>>(
+                request: http::Request<T>,
+            ) -> Result<Self, $crate::Error> {
+                // A query-only or path-only request may arrive with a genuinely empty body
+                // (as opposed to a `{}` produced by this crate's own `try_into_http_request`);
+                // treat it the same as an explicit empty JSON object.
+                let incoming_body: IncomingRequestBody = {
+                    let body_bytes = request.body().as_ref();
+                    let body_bytes: &[u8] = if body_bytes.is_empty() { b"{}" } else { body_bytes };
+                    serde_json::from_slice(body_bytes).map_err($crate::Error::new)?
+                };
+
+                #[allow(unused_variables)]
+                let incoming_query: IncomingRequestQuery =
+                    serde_urlencoded::from_str(request.uri().query().unwrap_or(""))
+                        .map_err($crate::Error::new)?;
+
+                #[allow(unused_variables)]
+                let path_template = $decode_path;
+
+                Ok(Request {
+                    $(
+                        $path_field: $crate::path_segment(
+                            request.uri().path(),
+                            path_template,
+                            concat!(":", stringify!($path_field)),
+                        ).map_err($crate::Error::new)?,
+                    )*
+                    $( $query_field: incoming_query.$query_field, )*
+                    $(
+                        $query_vec_field: $crate::query_pair_sequence(
+                            request.uri().query().unwrap_or(""),
+                            stringify!($query_vec_field),
+                        )?,
+                    )*
+                    $(
+                        $header_field: request
+                            .headers()
+                            .get($header_name)
+                            .ok_or_else(|| $crate::Error::new(
+                                concat!("missing header: ", $header_name),
+                            ))
+                            .and_then(|value| value.to_str().map_err($crate::Error::new))
+                            .and_then(|value| value.parse().map_err($crate::Error::new))?,
+                    )*
+                    $( $body_field: incoming_body.$body_field, )*
+                })
+            }
+        }
+    };
+}
+
+/// Implementation detail of `ruma_api!`. Not public API.
+#[macro_export]
+#[doc(hidden)]
+macro_rules! __ruma_api_response {
+    ( error: $error_ty:ty, fields: { $($fields:tt)* } ) => {
+        __ruma_api_response! {
+            @munch
+            error: $error_ty,
+            fields_left: { $($fields)* }
+            header: []
+            body: []
+        }
+    };
+
+    (
+        @munch
+        error: $error_ty:ty,
+        fields_left: {
+            $(#[doc = $doc:expr])*
+            #[ruma_api(header = $header_name:expr)]
+            pub $field:ident: $ty:ty,
+            $($rest:tt)*
+        }
+        header: [ $($header_field:tt)* ]
+        body: [ $($body_field:tt)* ]
+    ) => {
+        __ruma_api_response! {
+            @munch
+            error: $error_ty,
+            fields_left: { $($rest)* }
+            header: [ $($header_field)* { [$(#[doc = $doc])*] $field: $ty, $header_name } ]
+            body: [ $($body_field)* ]
+        }
+    };
+
+    (
+        @munch
+        error: $error_ty:ty,
+        fields_left: {
+            $(#[$doc:meta])*
+            pub $field:ident: $ty:ty,
+            $($rest:tt)*
+        }
+        header: [ $($header_field:tt)* ]
+        body: [ $($body_field:tt)* ]
+    ) => {
+        __ruma_api_response! {
+            @munch
+            error: $error_ty,
+            fields_left: { $($rest)* }
+            header: [ $($header_field)* ]
+            body: [ $($body_field)* { [$(#[$doc])*] $field: $ty } ]
+        }
+    };
+
+    (
+        @munch
+        error: $error_ty:ty,
+        fields_left: {}
+        header: [ $({ [$(#[$header_doc:meta])*] $header_field:ident: $header_ty:ty, $header_name:expr })* ]
+        body: [ $({ [$(#[$body_doc:meta])*] $body_field:ident: $body_ty:ty })* ]
+    ) => {
+        /// Data in the response from this endpoint.
+        #[derive(Clone, Debug)]
+        pub struct Response {
+            $( $(#[$header_doc])* pub $header_field: $header_ty, )*
+            $( $(#[$body_doc])* pub $body_field: $body_ty, )*
+        }
+
+        #[derive(Serialize)]
+        struct ResponseBody {
+            $( $body_field: $body_ty, )*
+        }
+
+        #[derive(Deserialize)]
+        struct IncomingResponseBody {
+            $( $body_field: $body_ty, )*
+        }
+
+        impl $crate::OutgoingResponse for Response {
+            fn try_into_http_response<T: Default + bytes::BufMut>(
+                self,
+            ) -> Result<http::Response<T>, $crate::Error> {
+                let response_body = ResponseBody {
+                    $( $body_field: self.$body_field, )*
+                };
+
+                let mut body = T::default();
+                body.put_slice(
+                    &serde_json::to_vec(&response_body).map_err($crate::Error::new)?,
+                );
+
+                #[allow(unused_mut)]
+                let mut builder = http::Response::builder().status(200);
+                $( builder = builder.header($header_name, self.$header_field.to_string()); )*
+                builder.body(body).map_err($crate::Error::from)
+            }
+        }
+
+        impl $crate::IncomingResponse for Response {
+            type EndpointError = $error_ty;
+
+            fn try_from_http_response<T: AsRef<[u8]>>(
+                response: http::Response<T>,
+            ) -> Result<Self, $crate::FromHttpResponseError<$error_ty>> {
+                if !response.status().is_success() {
+                    return Err($crate::FromHttpResponseError::Http(
+                        match serde_json::from_slice::<$error_ty>(response.body().as_ref()) {
+                            Ok(error) => $crate::ServerError::Known(error),
+                            Err(_) => $crate::ServerError::Unknown(response.status()),
+                        },
+                    ));
                 }
+
+                // A response with no `response` body fields may arrive with a genuinely empty
+                // body (as opposed to a `{}` produced by this crate's own
+                // `try_into_http_response`); treat it the same as an explicit empty JSON object.
+                let incoming_body: IncomingResponseBody = {
+                    let body_bytes = response.body().as_ref();
+                    let body_bytes: &[u8] = if body_bytes.is_empty() { b"{}" } else { body_bytes };
+                    serde_json::from_slice(body_bytes).map_err($crate::Error::new)?
+                };
+
+                Ok(Response {
+                    $(
+                        $header_field: response
+                            .headers()
+                            .get($header_name)
+                            .ok_or_else(|| $crate::Error::new(
+                                concat!("missing header: ", $header_name),
+                            ))
+                            .and_then(|value| value.to_str().map_err($crate::Error::new))
+                            .and_then(|value| value.parse().map_err($crate::Error::new))?,
+                    )*
+                    $( $body_field: incoming_body.$body_field, )*
+                })
             }
         }
-    }
+    };
 }
@@ -0,0 +1,99 @@
+use {IncomingRequest, IncomingResponse, MatrixVersion, OutgoingRequest, OutgoingResponse,
+     VersionHistory};
+
+mod get_thing {
+    ruma_api! {
+        metadata: {
+            description: "Get a thing.",
+            method: GET,
+            name: "get_thing",
+            path: "/_matrix/client/r0/things/:thing_id",
+            rate_limited: false,
+            auth_scheme: None,
+        }
+
+        request: {
+            /// The thing to fetch.
+            #[ruma_api(path)]
+            pub thing_id: String,
+            /// Whether to include extra detail.
+            #[ruma_api(query)]
+            pub verbose: bool,
+            /// Extra ids of interest.
+            #[ruma_api(query)]
+            pub related_to: Vec<String>,
+            /// An opaque client-supplied identifier for the request.
+            #[ruma_api(header = "X-Request-Id")]
+            pub request_id: String,
+            /// A free-form note to attach to the request.
+            pub note: String,
+        }
+
+        response: {
+            /// An opaque identifier for the response.
+            #[ruma_api(header = "X-Response-Id")]
+            pub response_id: String,
+            /// The fetched value.
+            pub value: u32,
+        }
+    }
+}
+
+#[test]
+fn request_round_trips_through_http_request() {
+    let request = get_thing::Request {
+        thing_id: "foo bar".to_owned(),
+        verbose: true,
+        related_to: vec!["a".to_owned(), "b".to_owned()],
+        request_id: "req-1".to_owned(),
+        note: "hello, world".to_owned(),
+    };
+
+    let http_request = request
+        .try_into_http_request::<Vec<u8>>(&[MatrixVersion::R0])
+        .unwrap();
+
+    let round_tripped = get_thing::Request::try_from_http_request(http_request).unwrap();
+
+    assert_eq!(round_tripped.thing_id, "foo bar");
+    assert_eq!(round_tripped.verbose, true);
+    assert_eq!(round_tripped.related_to, vec!["a".to_owned(), "b".to_owned()]);
+    assert_eq!(round_tripped.request_id, "req-1");
+    assert_eq!(round_tripped.note, "hello, world");
+}
+
+#[test]
+fn response_round_trips_through_http_response() {
+    let response = get_thing::Response {
+        response_id: "resp-1".to_owned(),
+        value: 42,
+    };
+
+    let http_response = response.try_into_http_response::<Vec<u8>>().unwrap();
+    let round_tripped = get_thing::Response::try_from_http_response(http_response).unwrap();
+
+    assert_eq!(round_tripped.response_id, "resp-1");
+    assert_eq!(round_tripped.value, 42);
+}
+
+#[test]
+fn select_path_picks_newest_path_at_or_below_the_newest_supported_version() {
+    let history = VersionHistory::new(
+        &[
+            (MatrixVersion::R0, "/_matrix/client/r0/things"),
+            (MatrixVersion::V1_1, "/_matrix/client/v1.1/things"),
+        ],
+        None,
+        None,
+    );
+
+    assert_eq!(
+        history.select_path(&[MatrixVersion::V1_3]),
+        Some("/_matrix/client/v1.1/things"),
+    );
+    assert_eq!(
+        history.select_path(&[MatrixVersion::R0]),
+        Some("/_matrix/client/r0/things"),
+    );
+    assert_eq!(history.select_path(&[]), None);
+}